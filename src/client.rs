@@ -0,0 +1,124 @@
+//! Blocking IPC client with request/response correlation.
+//!
+//! `Client` keeps one connection open, writes framed `MethodCall`s to it,
+//! and demultiplexes the responses on a dedicated reader thread by matching
+//! the `id` field against a table of outstanding calls, so several `call`s
+//! can be in flight on the same socket at once.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use jsonrpc_core::{Error, Id, MethodCall, Output, Params, Response, Value, Version};
+
+use transport;
+
+type Pending = Arc<Mutex<HashMap<u64, Sender<Response>>>>;
+
+/// A connected IPC client.
+pub struct Client {
+    socket: Mutex<transport::ClientStream>,
+    pending: Pending,
+    next_id: AtomicUsize,
+}
+
+impl Client {
+    /// Connect to the IPC endpoint at `addr` and start demultiplexing responses.
+    pub fn connect(addr: &str) -> io::Result<Client> {
+        let socket = transport::connect(addr)?;
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+
+        let reader_socket = socket.try_clone()?;
+        let reader_pending = pending.clone();
+        thread::spawn(move || read_loop(reader_socket, reader_pending));
+
+        Ok(Client {
+            socket: Mutex::new(socket),
+            pending: pending,
+            next_id: AtomicUsize::new(1),
+        })
+    }
+
+    /// Call `method` with `params` and block for the decoded result.
+    pub fn call(&self, method: &str, params: Params) -> Result<Value, Error> {
+        let response = self.send(method, params).map_err(|_| Error::internal_error())?;
+        match response {
+            Response::Single(Output::Success(success)) => Ok(success.result),
+            Response::Single(Output::Failure(failure)) => Err(failure.error),
+            Response::Batch(_) => Err(Error::internal_error()),
+        }
+    }
+
+    /// Send `method` with `params` and block for the raw decoded `Response`.
+    pub fn send(&self, method: &str, params: Params) -> io::Result<Response> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) as u64;
+        let (tx, rx) = channel();
+        self.pending.lock().unwrap().insert(id, tx);
+
+        let request = MethodCall {
+            jsonrpc: Some(Version::V2),
+            method: method.to_owned(),
+            params: Some(params),
+            id: Id::Num(id),
+        };
+        let mut payload = ::serde_json::to_string(&request).expect("MethodCall always serializes");
+        payload.push('\n');
+        self.socket.lock().unwrap().write_all(payload.as_bytes())?;
+
+        rx.recv().map_err(|_| io::Error::new(io::ErrorKind::Other, "ipc connection closed before response arrived"))
+    }
+}
+
+impl Drop for Client {
+    /// Shut the socket down so `read_loop`'s blocking read unblocks and the
+    /// reader thread exits, instead of leaking a thread and a socket for
+    /// every `Client` dropped while the server side stays up. On Windows
+    /// `transport::shutdown` does nothing, so there the reader thread exits
+    /// only once the server closes its end of the pipe.
+    fn drop(&mut self) {
+        transport::shutdown(&self.socket.lock().unwrap());
+    }
+}
+
+fn read_loop(socket: transport::ClientStream, pending: Pending) {
+    let mut reader = BufReader::new(socket);
+    let mut line = String::new();
+    while let Ok(n) = reader.read_line(&mut line) {
+        if n == 0 {
+            break;
+        }
+
+        if let Ok(response) = ::serde_json::from_str::<Response>(&line) {
+            if let Some(id) = response_id(&response) {
+                if let Some(tx) = pending.lock().unwrap().remove(&id) {
+                    let _ = tx.send(response);
+                }
+            }
+        }
+
+        line.clear();
+    }
+
+    // The connection is gone (EOF or an I/O error). Drop every outstanding
+    // `Sender` so any `Client::call`/`Client::send` still blocked on
+    // `rx.recv()` wakes up with an error instead of hanging forever.
+    pending.lock().unwrap().clear();
+}
+
+fn response_id(response: &Response) -> Option<u64> {
+    match *response {
+        Response::Single(Output::Success(ref success)) => as_u64(&success.id),
+        Response::Single(Output::Failure(ref failure)) => as_u64(&failure.id),
+        Response::Batch(_) => None,
+    }
+}
+
+fn as_u64(id: &Id) -> Option<u64> {
+    match *id {
+        Id::Num(n) => Some(n),
+        _ => None,
+    }
+}
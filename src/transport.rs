@@ -0,0 +1,82 @@
+//! Platform transport selection.
+//!
+//! The rest of the crate talks to `transport::Listener` / `transport::Stream`
+//! without caring whether the underlying channel is a Unix domain socket or a
+//! Windows named pipe. Unix keeps the existing `mio::unix` socket; Windows
+//! dispatches to a named pipe rooted at `\\.\pipe\`, selected purely by
+//! `cfg(windows)` so `Server::new` keeps taking a single string address on
+//! either platform.
+
+#[cfg(unix)]
+pub use self::unix::{bind, connect, shutdown, ClientStream, Listener, Stream};
+
+#[cfg(windows)]
+pub use self::windows::{bind, connect, shutdown, ClientStream, Listener, Stream};
+
+#[cfg(unix)]
+mod unix {
+    use mio::unix::{UnixListener, UnixStream};
+    use std::io;
+    use std::net::Shutdown;
+    use std::os::unix::net::UnixStream as BlockingUnixStream;
+
+    pub type Listener = UnixListener;
+    pub type Stream = UnixStream;
+    pub type ClientStream = BlockingUnixStream;
+
+    /// Bind a Unix domain socket at `addr`, removing any stale socket file left behind.
+    pub fn bind(addr: &str) -> io::Result<Listener> {
+        let _ = ::std::fs::remove_file(addr); // ignore error (if no file)
+        UnixListener::bind(addr)
+    }
+
+    /// Connect to a Unix domain socket at `addr` for client use.
+    pub fn connect(addr: &str) -> io::Result<ClientStream> {
+        BlockingUnixStream::connect(addr)
+    }
+
+    /// Shut down both halves of `stream`. This is socket-level, not
+    /// per-descriptor, so it also unblocks a `read` any clone of `stream`
+    /// (e.g. on another thread) is blocked in.
+    pub fn shutdown(stream: &ClientStream) {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    extern crate mio_named_pipes;
+
+    use self::mio_named_pipes::NamedPipe;
+    use std::fs::{File, OpenOptions};
+    use std::io;
+
+    pub type Listener = NamedPipe;
+    pub type Stream = NamedPipe;
+    pub type ClientStream = File;
+
+    /// Create the first instance of a named pipe at `\\.\pipe\<addr>`, with
+    /// `addr`'s path separators folded away since pipe names are flat.
+    pub fn bind(addr: &str) -> io::Result<Listener> {
+        let pipe_name = to_pipe_name(addr);
+        NamedPipe::new(pipe_name)
+    }
+
+    /// Open the client side of a named pipe at `\\.\pipe\<addr>`. Unlike the
+    /// server side, a pipe client is just a file handle opened for read/write.
+    pub fn connect(addr: &str) -> io::Result<ClientStream> {
+        let pipe_name = to_pipe_name(addr);
+        OpenOptions::new().read(true).write(true).open(pipe_name)
+    }
+
+    /// A plain file handle has no portable equivalent of socket shutdown, so
+    /// this can't interrupt a blocking read in progress on another handle to
+    /// the same pipe instance; closing `Client`'s own handle on drop is the
+    /// best this transport can do here.
+    pub fn shutdown(_stream: &ClientStream) {}
+
+    fn to_pipe_name(addr: &str) -> String {
+        let trimmed = addr.trim_start_matches('/').replace('/', "-").replace('\\', "-");
+        format!(r"\\.\pipe\{}", trimmed)
+    }
+}